@@ -0,0 +1,81 @@
+use anyhow::{anyhow, bail, ensure, Result};
+use std::ops::Range;
+
+/// The bytes of the value stored under `key` in the bencoded dictionary
+/// `buf`, exactly as they appear in the input.
+///
+/// Decoding and encoding again only keeps the keys we model and may reorder
+/// them, which is fine for reading values but not for anything that hashes
+/// the original bytes, like the info hash.
+pub fn dict_value<'a>(buf: &'a [u8], key: &[u8]) -> Result<Option<&'a [u8]>> {
+    ensure!(buf.first() == Some(&b'd'), "not a bencoded dictionary");
+    let mut pos = 1;
+    while buf.get(pos) != Some(&b'e') {
+        let k = string(buf, pos)?;
+        let end = value_end(buf, k.end)?;
+        if &buf[k.clone()] == key {
+            return Ok(Some(&buf[k.end..end]));
+        }
+        pos = end;
+    }
+    Ok(None)
+}
+
+/// Offset of the first byte after the value starting at `pos`
+fn value_end(buf: &[u8], pos: usize) -> Result<usize> {
+    match buf.get(pos) {
+        Some(b'i') => Ok(find(buf, pos, b'e')? + 1),
+        Some(b'l') | Some(b'd') => {
+            let mut pos = pos + 1;
+            while buf.get(pos) != Some(&b'e') {
+                pos = value_end(buf, pos)?;
+            }
+            Ok(pos + 1)
+        }
+        Some(b'0'..=b'9') => Ok(string(buf, pos)?.end),
+        Some(&c) => bail!("unexpected {:?} at offset {}", c as char, pos),
+        None => bail!("unexpected end of data"),
+    }
+}
+
+/// Range of the contents of the byte string starting at `pos`
+fn string(buf: &[u8], pos: usize) -> Result<Range<usize>> {
+    let colon = find(buf, pos, b':')?;
+    let len: usize = std::str::from_utf8(&buf[pos..colon])?.parse()?;
+    let start = colon + 1;
+    match start.checked_add(len) {
+        Some(end) if end <= buf.len() => Ok(start..end),
+        _ => bail!("unexpected end of data"),
+    }
+}
+
+fn find(buf: &[u8], pos: usize, byte: u8) -> Result<usize> {
+    buf[pos..]
+        .iter()
+        .position(|&b| b == byte)
+        .map(|i| pos + i)
+        .ok_or(anyhow!("unexpected end of data"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_nested_values_verbatim() {
+        let buf = b"d8:announce3:url4:infod6:lengthi5e4:name1:x6:source3:abce5:nodesll1:ai1eeee";
+        assert_eq!(
+            dict_value(buf, b"info").unwrap(),
+            Some(&b"d6:lengthi5e4:name1:x6:source3:abce"[..])
+        );
+        assert_eq!(dict_value(buf, b"nodes").unwrap(), Some(&b"ll1:ai1eee"[..]));
+        assert_eq!(dict_value(buf, b"comment").unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_truncated_input() {
+        assert!(dict_value(b"d4:infod6:lengthi5e", b"info").is_err());
+        assert!(dict_value(b"d4:info10:abc", b"info").is_err());
+        assert!(dict_value(b"l4:infoe", b"info").is_err());
+    }
+}
@@ -0,0 +1,259 @@
+use crate::{load_torrent, Torrent};
+use anyhow::Result;
+use serde::Serialize;
+use std::path::Path;
+
+/// One file of a torrent, with the path relative to the torrent's root
+#[derive(Debug, Clone, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+struct FileEntry {
+    path: String,
+    length: i64,
+}
+
+#[derive(Debug, Serialize)]
+struct Summary {
+    info_hash: String,
+    name: String,
+    piece_length: i64,
+    pieces: usize,
+    private: bool,
+    trackers: Vec<String>,
+    /// Infohashes of torrents the creator says share files with this one
+    similar: Vec<String>,
+    collections: Vec<String>,
+}
+
+/// Whether a client that has the data of one torrent can seed the other
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum CrossSeed {
+    /// Same infohash, it's the same torrent
+    Identical,
+    /// Same files and piece hashes, the data can be reused as is
+    Yes,
+    /// Same files but different pieces, the data has to be rechecked
+    Recheck,
+    /// The file lists differ
+    No,
+}
+
+#[derive(Debug, Serialize)]
+struct TorrentDiff {
+    a: Summary,
+    b: Summary,
+    files_only_in_a: Vec<FileEntry>,
+    files_only_in_b: Vec<FileEntry>,
+    trackers_only_in_a: Vec<String>,
+    trackers_only_in_b: Vec<String>,
+    /// One of the torrents lists the other one as similar (BEP 38)
+    listed_as_similar: bool,
+    same_pieces: bool,
+    cross_seedable: CrossSeed,
+}
+
+fn files(torrent: &Torrent) -> Vec<FileEntry> {
+    let mut files: Vec<FileEntry> = match &torrent.info.files {
+        Some(files) => files
+            .iter()
+            .map(|f| FileEntry {
+                path: f.path.join("/"),
+                length: f.length,
+            })
+            .collect(),
+        None => vec![FileEntry {
+            path: torrent.info.name.clone(),
+            length: torrent.info.length.unwrap_or(0),
+        }],
+    };
+    files.sort();
+    files
+}
+
+/// All tracker urls, from `announce-list` and `announce`, without duplicates
+fn trackers(torrent: &Torrent) -> Vec<String> {
+    let mut trackers: Vec<String> = torrent
+        .announce_list
+        .iter()
+        .flatten()
+        .flatten()
+        .chain(&torrent.announce)
+        .cloned()
+        .collect();
+    trackers.sort();
+    trackers.dedup();
+    trackers
+}
+
+/// Values of a BEP 38 key from both the info dictionary and the top level
+fn merged<T>(info: &Option<Vec<T>>, top: &Option<Vec<T>>, f: impl Fn(&T) -> String) -> Vec<String> {
+    let mut values: Vec<String> = info.iter().chain(top).flatten().map(f).collect();
+    values.sort();
+    values.dedup();
+    values
+}
+
+fn summary(torrent: &Torrent) -> Summary {
+    Summary {
+        info_hash: torrent.info_hash().to_string(),
+        name: torrent.info.name.clone(),
+        piece_length: torrent.info.piece_length,
+        pieces: torrent.info.pieces.len() / 20,
+        private: torrent.info.private == Some(1),
+        trackers: trackers(torrent),
+        similar: merged(&torrent.info.similar, &torrent.similar, |h| hex::encode(h)),
+        collections: merged(
+            &torrent.info.collections,
+            &torrent.collections,
+            String::clone,
+        ),
+    }
+}
+
+/// Elements of `a` that are not in `b`
+fn missing<T: PartialEq + Clone>(a: &[T], b: &[T]) -> Vec<T> {
+    a.iter().filter(|x| !b.contains(x)).cloned().collect()
+}
+
+fn compare(a: &Torrent, b: &Torrent) -> TorrentDiff {
+    let (a_files, b_files) = (files(a), files(b));
+    let files_only_in_a = missing(&a_files, &b_files);
+    let files_only_in_b = missing(&b_files, &a_files);
+    // Paths are relative to the torrent's root, so a multi file torrent
+    // with a differently named directory still has the same files
+    let same_files = files_only_in_a.is_empty() && files_only_in_b.is_empty();
+    let same_pieces = a.info.piece_length == b.info.piece_length && a.info.pieces == b.info.pieces;
+
+    let a_summary = summary(a);
+    let b_summary = summary(b);
+    let cross_seedable = if a_summary.info_hash == b_summary.info_hash {
+        CrossSeed::Identical
+    } else if same_files && same_pieces {
+        CrossSeed::Yes
+    } else if same_files {
+        CrossSeed::Recheck
+    } else {
+        CrossSeed::No
+    };
+
+    let listed_as_similar = a_summary.similar.contains(&b_summary.info_hash)
+        || b_summary.similar.contains(&a_summary.info_hash);
+
+    TorrentDiff {
+        trackers_only_in_a: missing(&a_summary.trackers, &b_summary.trackers),
+        trackers_only_in_b: missing(&b_summary.trackers, &a_summary.trackers),
+        a: a_summary,
+        b: b_summary,
+        files_only_in_a,
+        files_only_in_b,
+        listed_as_similar,
+        same_pieces,
+        cross_seedable,
+    }
+}
+
+/// Compare two torrents and print the differences, either for humans or as JSON
+pub fn diff(a: &Path, b: &Path, json: bool) -> Result<()> {
+    let diff = compare(&load_torrent(a)?, &load_torrent(b)?);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&diff)?);
+        return Ok(());
+    }
+
+    let row = |label: &str, a: String, b: String| {
+        let marker = if a == b { ' ' } else { '*' };
+        println!("{} {:<14}{:<42}{}", marker, label, a, b);
+    };
+    row(
+        "info hash",
+        diff.a.info_hash.clone(),
+        diff.b.info_hash.clone(),
+    );
+    row("name", diff.a.name.clone(), diff.b.name.clone());
+    row(
+        "piece length",
+        diff.a.piece_length.to_string(),
+        diff.b.piece_length.to_string(),
+    );
+    row(
+        "pieces",
+        diff.a.pieces.to_string(),
+        diff.b.pieces.to_string(),
+    );
+    row(
+        "private",
+        diff.a.private.to_string(),
+        diff.b.private.to_string(),
+    );
+    row(
+        "collections",
+        diff.a.collections.join(", "),
+        diff.b.collections.join(", "),
+    );
+
+    for (side, files) in [("a", &diff.files_only_in_a), ("b", &diff.files_only_in_b)] {
+        for f in files {
+            println!("file only in {}: {} ({} bytes)", side, f.path, f.length);
+        }
+    }
+    for (side, trackers) in [
+        ("a", &diff.trackers_only_in_a),
+        ("b", &diff.trackers_only_in_b),
+    ] {
+        for t in trackers {
+            println!("tracker only in {}: {}", side, t);
+        }
+    }
+
+    if diff.listed_as_similar {
+        println!("the torrents are listed as similar to each other");
+    }
+    let verdict = match diff.cross_seedable {
+        CrossSeed::Identical => "identical torrents",
+        CrossSeed::Yes => "cross-seedable, the data is identical",
+        CrossSeed::Recheck => "same files but different pieces, cross-seedable after a recheck",
+        CrossSeed::No => "not cross-seedable, the files differ",
+    };
+    println!("{}", verdict);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn torrent(info: &[u8]) -> Torrent {
+        let buf = [&b"d8:announce17:http://t/announce4:info"[..], info, b"e"].concat();
+        Torrent::from_bytes(&buf).unwrap()
+    }
+
+    #[test]
+    fn unmodelled_info_keys_change_the_info_hash() {
+        let a = torrent(b"d6:lengthi5e4:name1:x12:piece lengthi16384e6:pieces20:aaaaaaaaaaaaaaaaaaaa6:source1:Ae");
+        let b = torrent(b"d6:lengthi5e4:name1:x12:piece lengthi16384e6:pieces20:aaaaaaaaaaaaaaaaaaaa6:source1:Be");
+        let diff = compare(&a, &b);
+        assert_ne!(diff.a.info_hash, diff.b.info_hash);
+        assert_eq!(diff.cross_seedable, CrossSeed::Yes);
+        assert_eq!(compare(&a, &a).cross_seedable, CrossSeed::Identical);
+    }
+
+    #[test]
+    fn similar_torrents_are_recognized() {
+        let a =
+            torrent(b"d6:lengthi5e4:name1:x12:piece lengthi16384e6:pieces20:aaaaaaaaaaaaaaaaaaaae");
+        // Entries of the wrong length are shown, not rejected
+        let b = torrent(
+            &[
+                &b"d6:lengthi5e4:name1:y12:piece lengthi16384e6:pieces20:aaaaaaaaaaaaaaaaaaaa7:similarl20:"[..],
+                a.info_hash().as_bytes(),
+                b"3:abcee",
+            ]
+            .concat(),
+        );
+        let diff = compare(&a, &b);
+        assert!(diff.listed_as_similar);
+        assert_eq!(
+            diff.b.similar,
+            vec!["616263".to_string(), a.info_hash().to_string()]
+        );
+    }
+}
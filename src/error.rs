@@ -0,0 +1,47 @@
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// Failure classes that are reported to the caller through distinct exit codes.
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("failed to parse torrent file: {0:#?}")]
+    Parse(PathBuf),
+    #[error("tracker {0} is unreachable")]
+    TrackerUnreachable(String),
+    #[error("no space left on device")]
+    DiskFull,
+    #[error("piece {0} failed the hash check")]
+    HashMismatch(usize),
+    #[error("interrupted")]
+    Interrupted,
+}
+
+impl Error {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Parse(_) => 2,
+            Error::TrackerUnreachable(_) => 3,
+            Error::DiskFull => 4,
+            Error::HashMismatch(_) => 5,
+            Error::Interrupted => 130,
+        }
+    }
+}
+
+/// Map any error that bubbled up to `main` to the exit code of the process.
+/// Errors that don't fall into one of the classes above exit with 1.
+pub fn exit_code(err: &anyhow::Error) -> i32 {
+    if let Some(e) = err.downcast_ref::<Error>() {
+        return e.exit_code();
+    }
+    let disk_full = err.chain().any(|cause| {
+        cause
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(|e| e.kind() == std::io::ErrorKind::StorageFull)
+    });
+    if disk_full {
+        Error::DiskFull.exit_code()
+    } else {
+        1
+    }
+}
@@ -0,0 +1,182 @@
+use anyhow::{bail, Result};
+use serde::de::{self, Deserializer, Visitor};
+use serde::{Deserialize, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Identifies a torrent, the hash of its info dictionary.
+///
+/// v1 torrents use SHA-1, v2 torrents (BEP 52) SHA-256. Bencoded it is a raw
+/// byte string, for humans it is written as lowercase hex.
+#[derive(Debug, Clone, Copy)]
+#[allow(dead_code)]
+pub enum InfoHash {
+    V1([u8; 20]),
+    V2([u8; 32]),
+}
+
+impl InfoHash {
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            InfoHash::V1(hash) => hash,
+            InfoHash::V2(hash) => hash,
+        }
+    }
+
+    /// The 20 bytes sent to trackers and peers. v2 hashes are truncated,
+    /// which is how BEP 52 makes them fit into the existing protocols.
+    pub fn truncated(&self) -> [u8; 20] {
+        self.as_bytes()[..20].try_into().unwrap()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<InfoHash> {
+        match bytes.len() {
+            20 => Ok(InfoHash::V1(bytes.try_into().unwrap())),
+            32 => Ok(InfoHash::V2(bytes.try_into().unwrap())),
+            n => bail!("an info hash is 20 or 32 bytes long, not {}", n),
+        }
+    }
+}
+
+/// Compares in constant time, so the position of the first differing byte
+/// can't be learned from timing
+impl PartialEq for InfoHash {
+    fn eq(&self, other: &InfoHash) -> bool {
+        let (a, b) = (self.as_bytes(), other.as_bytes());
+        a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+    }
+}
+
+impl Eq for InfoHash {}
+
+impl fmt::Display for InfoHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.as_bytes()))
+    }
+}
+
+/// Parses hex (v1 or v2) and the base32 form of v1 hashes found in older magnet links
+impl FromStr for InfoHash {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<InfoHash> {
+        match s.len() {
+            40 | 64 => InfoHash::from_bytes(&hex::decode(s)?),
+            32 => InfoHash::from_bytes(&base32_decode(s)?),
+            _ => bail!("{:?} is not a hex or base32 encoded info hash", s),
+        }
+    }
+}
+
+/// Decode RFC 4648 base32 without padding
+fn base32_decode(s: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(s.len() * 5 / 8);
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in s.bytes() {
+        let value = match BASE32_ALPHABET
+            .iter()
+            .position(|&a| a == c.to_ascii_uppercase())
+        {
+            Some(value) => value as u32,
+            None => bail!("invalid base32 character {:?}", c as char),
+        };
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+    Ok(bytes)
+}
+
+impl Serialize for InfoHash {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(self.as_bytes())
+    }
+}
+
+impl<'de> Deserialize<'de> for InfoHash {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<InfoHash, D::Error> {
+        struct InfoHashVisitor;
+
+        impl Visitor<'_> for InfoHashVisitor {
+            type Value = InfoHash;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a 20 or 32 byte info hash")
+            }
+
+            fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<InfoHash, E> {
+                InfoHash::from_bytes(v).map_err(E::custom)
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<InfoHash, E> {
+                v.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_bytes(InfoHashVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEX: &str = "c12fe1c06bba254a9dc9f519b335aa7c1367a88a";
+
+    #[test]
+    fn parses_hex_and_base32() {
+        let v1: InfoHash = HEX.parse().unwrap();
+        assert_eq!(v1.to_string(), HEX);
+        assert_eq!(
+            "C12FE1C06BBA254A9DC9F519B335AA7C1367A88A"
+                .parse::<InfoHash>()
+                .unwrap(),
+            v1
+        );
+        assert_eq!(
+            "YEX6DQDLXISUVHOJ6UM3GNNKPQJWPKEK"
+                .parse::<InfoHash>()
+                .unwrap(),
+            v1
+        );
+        assert_eq!(
+            "yex6dqdlxisuvhoj6um3gnnkpqjwpkek"
+                .parse::<InfoHash>()
+                .unwrap(),
+            v1
+        );
+
+        let v2: InfoHash = "ab".repeat(32).parse().unwrap();
+        assert!(matches!(v2, InfoHash::V2(_)));
+        assert_eq!(v2.truncated(), [0xab; 20]);
+        assert_ne!(v1, v2);
+    }
+
+    #[test]
+    fn rejects_other_lengths_and_characters() {
+        for s in [
+            "",
+            "abc",
+            &HEX[1..],
+            &"0".repeat(41),
+            &"g".repeat(40),
+            "YEX6DQDLXISUVHOJ6UM3GNNKPQJWPKE1",
+        ] {
+            assert!(s.parse::<InfoHash>().is_err(), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn bencodes_as_raw_bytes() {
+        let v1: InfoHash = HEX.parse().unwrap();
+        let buf = serde_bencode::to_bytes(&v1).unwrap();
+        assert_eq!(&buf[..3], b"20:");
+        assert_eq!(serde_bencode::from_bytes::<InfoHash>(&buf).unwrap(), v1);
+        assert!(serde_bencode::from_bytes::<InfoHash>(b"3:abc").is_err());
+    }
+}
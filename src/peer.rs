@@ -0,0 +1,440 @@
+use crate::error::Error;
+use crate::info_hash::InfoHash;
+use crate::peer_id::PeerId;
+use anyhow::{bail, ensure, Context, Result};
+use sha1::{Digest, Sha1};
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+const PROTOCOL: &[u8; 19] = b"BitTorrent protocol";
+
+/// Size of the blocks a piece is requested in, 16KiB is what every client accepts
+const BLOCK_SIZE: usize = 1 << 14;
+
+/// Number of block requests that are kept in flight per peer
+const MAX_BACKLOG: usize = 5;
+
+/// Upper bound for a single message, large enough for the bitfield of any sane torrent
+const MAX_MESSAGE_LEN: usize = 1 << 21;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+const READ_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// The first message exchanged on every connection, in both directions
+#[derive(Debug)]
+pub struct Handshake {
+    pub info_hash: InfoHash,
+    pub peer_id: PeerId,
+}
+
+impl Handshake {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(68);
+        buf.push(PROTOCOL.len() as u8);
+        buf.extend_from_slice(PROTOCOL);
+        buf.extend_from_slice(&[0; 8]);
+        buf.extend_from_slice(&self.info_hash.truncated());
+        buf.extend_from_slice(self.peer_id.as_bytes());
+        buf
+    }
+
+    fn read_from<R: Read>(reader: &mut R) -> Result<Handshake> {
+        let mut buf = [0; 68];
+        reader.read_exact(&mut buf)?;
+        ensure!(
+            buf[0] as usize == PROTOCOL.len() && &buf[1..20] == PROTOCOL,
+            "peer does not speak the BitTorrent protocol"
+        );
+        let peer_id: [u8; 20] = buf[48..68].try_into().unwrap();
+        Ok(Handshake {
+            info_hash: InfoHash::V1(buf[28..48].try_into().unwrap()),
+            peer_id: peer_id.into(),
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Message {
+    KeepAlive,
+    Choke,
+    Unchoke,
+    Interested,
+    NotInterested,
+    Have(u32),
+    Bitfield(Vec<u8>),
+    Request {
+        index: u32,
+        begin: u32,
+        length: u32,
+    },
+    Piece {
+        index: u32,
+        begin: u32,
+        block: Vec<u8>,
+    },
+    Cancel {
+        index: u32,
+        begin: u32,
+        length: u32,
+    },
+    /// Messages from extensions we don't support, they are skipped
+    Unknown(u8),
+}
+
+fn u32_at(payload: &[u8], offset: usize) -> Result<u32> {
+    let bytes = payload
+        .get(offset..offset + 4)
+        .context("message payload is too short")?;
+    Ok(u32::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+impl Message {
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Message> {
+        let mut len = [0; 4];
+        reader.read_exact(&mut len)?;
+        let len = u32::from_be_bytes(len) as usize;
+        if len == 0 {
+            return Ok(Message::KeepAlive);
+        }
+        ensure!(
+            len <= MAX_MESSAGE_LEN,
+            "message of {} bytes is too long",
+            len
+        );
+
+        let mut buf = vec![0; len];
+        reader.read_exact(&mut buf)?;
+        let payload = &buf[1..];
+        let msg = match buf[0] {
+            0 => Message::Choke,
+            1 => Message::Unchoke,
+            2 => Message::Interested,
+            3 => Message::NotInterested,
+            4 => Message::Have(u32_at(payload, 0)?),
+            5 => Message::Bitfield(payload.to_vec()),
+            6 => Message::Request {
+                index: u32_at(payload, 0)?,
+                begin: u32_at(payload, 4)?,
+                length: u32_at(payload, 8)?,
+            },
+            7 => Message::Piece {
+                index: u32_at(payload, 0)?,
+                begin: u32_at(payload, 4)?,
+                block: payload[8..].to_vec(),
+            },
+            8 => Message::Cancel {
+                index: u32_at(payload, 0)?,
+                begin: u32_at(payload, 4)?,
+                length: u32_at(payload, 8)?,
+            },
+            id => Message::Unknown(id),
+        };
+        Ok(msg)
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let mut payload = Vec::new();
+        let id: u8 = match self {
+            Message::KeepAlive => {
+                writer.write_all(&[0; 4])?;
+                return Ok(());
+            }
+            Message::Choke => 0,
+            Message::Unchoke => 1,
+            Message::Interested => 2,
+            Message::NotInterested => 3,
+            Message::Have(index) => {
+                payload.extend_from_slice(&index.to_be_bytes());
+                4
+            }
+            Message::Bitfield(bitfield) => {
+                payload.extend_from_slice(bitfield);
+                5
+            }
+            Message::Request {
+                index,
+                begin,
+                length,
+            } => {
+                payload.extend_from_slice(&index.to_be_bytes());
+                payload.extend_from_slice(&begin.to_be_bytes());
+                payload.extend_from_slice(&length.to_be_bytes());
+                6
+            }
+            Message::Piece {
+                index,
+                begin,
+                block,
+            } => {
+                payload.extend_from_slice(&index.to_be_bytes());
+                payload.extend_from_slice(&begin.to_be_bytes());
+                payload.extend_from_slice(block);
+                7
+            }
+            Message::Cancel {
+                index,
+                begin,
+                length,
+            } => {
+                payload.extend_from_slice(&index.to_be_bytes());
+                payload.extend_from_slice(&begin.to_be_bytes());
+                payload.extend_from_slice(&length.to_be_bytes());
+                8
+            }
+            Message::Unknown(id) => bail!("cannot send message with unknown id {}", id),
+        };
+        writer.write_all(&(payload.len() as u32 + 1).to_be_bytes())?;
+        writer.write_all(&[id])?;
+        writer.write_all(&payload)?;
+        Ok(())
+    }
+}
+
+/// An established connection to a remote peer
+pub struct Peer {
+    pub addr: SocketAddr,
+    stream: TcpStream,
+    choked: bool,
+    /// Number of pieces of the torrent, bounds what the peer can claim to have
+    pieces: usize,
+    bitfield: Vec<u8>,
+}
+
+impl Peer {
+    /// Connect to a peer, exchange handshakes and tell it that we want to download
+    pub fn connect(
+        addr: SocketAddr,
+        info_hash: &InfoHash,
+        peer_id: &PeerId,
+        pieces: usize,
+    ) -> Result<Peer> {
+        let mut stream = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)?;
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        stream.set_write_timeout(Some(READ_TIMEOUT))?;
+
+        let handshake = Handshake {
+            info_hash: *info_hash,
+            peer_id: *peer_id,
+        };
+        stream.write_all(&handshake.to_bytes())?;
+        let reply = Handshake::read_from(&mut stream)?;
+        ensure!(
+            reply.info_hash == InfoHash::V1(info_hash.truncated()),
+            "peer answered with a different info hash"
+        );
+
+        let mut peer = Peer {
+            addr,
+            stream,
+            choked: true,
+            pieces,
+            bitfield: vec![0; pieces.div_ceil(8)],
+        };
+        // Peers announce which pieces they have right after the handshake
+        peer.recv()?;
+        peer.send(&Message::Interested)?;
+        Ok(peer)
+    }
+
+    pub fn send(&mut self, msg: &Message) -> Result<()> {
+        msg.write_to(&mut self.stream)
+    }
+
+    /// Read the next message, keeping track of the state the peer communicates
+    pub fn recv(&mut self) -> Result<Message> {
+        let msg = Message::read_from(&mut self.stream)?;
+        match &msg {
+            Message::Choke => self.choked = true,
+            Message::Unchoke => self.choked = false,
+            Message::Bitfield(bitfield) => {
+                ensure!(
+                    bitfield.len() == self.bitfield.len(),
+                    "peer sent a bitfield of {} bytes for {} pieces",
+                    bitfield.len(),
+                    self.pieces
+                );
+                self.bitfield = bitfield.clone();
+            }
+            Message::Have(index) => {
+                let index = *index as usize;
+                ensure!(
+                    index < self.pieces,
+                    "peer has piece {} of only {}",
+                    index,
+                    self.pieces
+                );
+                self.bitfield[index / 8] |= 0x80 >> (index % 8);
+            }
+            _ => {}
+        }
+        Ok(msg)
+    }
+
+    pub fn has_piece(&self, index: usize) -> bool {
+        self.bitfield
+            .get(index / 8)
+            .is_some_and(|byte| byte & (0x80 >> (index % 8)) != 0)
+    }
+
+    /// Download a single piece block by block and verify it against its SHA-1 hash
+    pub fn download_piece(&mut self, index: usize, length: usize, hash: &[u8]) -> Result<Vec<u8>> {
+        let mut piece = vec![0; length];
+        let mut queue: VecDeque<usize> = (0..length).step_by(BLOCK_SIZE).collect();
+        let mut in_flight: Vec<usize> = Vec::new();
+
+        while !queue.is_empty() || !in_flight.is_empty() {
+            while !self.choked && in_flight.len() < MAX_BACKLOG {
+                let begin = match queue.pop_front() {
+                    Some(begin) => begin,
+                    None => break,
+                };
+                self.send(&Message::Request {
+                    index: index as u32,
+                    begin: begin as u32,
+                    length: BLOCK_SIZE.min(length - begin) as u32,
+                })?;
+                in_flight.push(begin);
+            }
+
+            match self.recv()? {
+                Message::Piece {
+                    index: i,
+                    begin,
+                    block,
+                } if i as usize == index => {
+                    let begin = begin as usize;
+                    let pos = match in_flight.iter().position(|&b| b == begin) {
+                        Some(pos) => pos,
+                        None => continue,
+                    };
+                    ensure!(
+                        block.len() == BLOCK_SIZE.min(length - begin),
+                        "peer sent a block of unexpected size"
+                    );
+                    in_flight.swap_remove(pos);
+                    piece[begin..begin + block.len()].copy_from_slice(&block);
+                }
+                // Choking discards all pending requests, so they have to be sent again
+                Message::Choke => queue.extend(in_flight.drain(..)),
+                _ => {}
+            }
+        }
+
+        if Sha1::digest(&piece)[..] != *hash {
+            return Err(Error::HashMismatch(index).into());
+        }
+        Ok(piece)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Connect to a fake peer that answers the handshake with `msg`
+    fn connect_to_peer_sending(msg: Message, pieces: usize) -> Result<Peer> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let info_hash = InfoHash::V1([1; 20]);
+        let peer = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let handshake = Handshake::read_from(&mut stream).unwrap();
+            stream.write_all(&handshake.to_bytes()).unwrap();
+            msg.write_to(&mut stream).unwrap();
+            // Keep the connection open until our side is done with it
+            let _ = stream.read_to_end(&mut Vec::new());
+        });
+        let res = Peer::connect(addr, &info_hash, &PeerId::random(), pieces);
+        if let Ok(peer) = &res {
+            peer.stream.shutdown(std::net::Shutdown::Write).unwrap();
+        }
+        peer.join().unwrap();
+        res
+    }
+
+    #[test]
+    fn messages_survive_a_round_trip() {
+        let messages = [
+            Message::KeepAlive,
+            Message::Choke,
+            Message::Unchoke,
+            Message::Interested,
+            Message::NotInterested,
+            Message::Have(0x01020304),
+            Message::Bitfield(vec![0xff, 0x80]),
+            Message::Request {
+                index: 1,
+                begin: 2 << 14,
+                length: 1 << 14,
+            },
+            Message::Piece {
+                index: 1,
+                begin: 0,
+                block: vec![7; 100],
+            },
+            Message::Cancel {
+                index: 1,
+                begin: 2,
+                length: 3,
+            },
+        ];
+        let mut buf = Vec::new();
+        for msg in &messages {
+            msg.write_to(&mut buf).unwrap();
+        }
+        assert_eq!(&buf[..9], [0, 0, 0, 0, 0, 0, 0, 1, 0]);
+        let mut reader = &buf[..];
+        for msg in messages {
+            assert_eq!(Message::read_from(&mut reader).unwrap(), msg);
+        }
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn malformed_messages_are_rejected() {
+        // Unknown ids are skipped, but can't be sent
+        assert_eq!(
+            Message::read_from(&mut &[0, 0, 0, 2, 20, 0][..]).unwrap(),
+            Message::Unknown(20)
+        );
+        assert!(Message::Unknown(20).write_to(&mut Vec::new()).is_err());
+        // A have without its index, and a length beyond the limit
+        assert!(Message::read_from(&mut &[0, 0, 0, 3, 4, 0, 0][..]).is_err());
+        assert!(Message::read_from(&mut &[0xff, 0xff, 0xff, 0xff][..]).is_err());
+    }
+
+    #[test]
+    fn handshake_survives_a_round_trip() {
+        let handshake = Handshake {
+            info_hash: InfoHash::V2([3; 32]),
+            peer_id: PeerId::from(*b"-TT0100-aaaaaaaaaaaa"),
+        };
+        let buf = handshake.to_bytes();
+        assert_eq!(buf.len(), 68);
+        assert_eq!(&buf[..20], b"\x13BitTorrent protocol");
+        let read = Handshake::read_from(&mut &buf[..]).unwrap();
+        // Only the truncated v2 hash goes over the wire
+        assert_eq!(read.info_hash, InfoHash::V1([3; 20]));
+        assert_eq!(read.peer_id, handshake.peer_id);
+
+        let mut buf = buf;
+        buf[1] = b'b';
+        assert!(Handshake::read_from(&mut &buf[..]).is_err());
+    }
+
+    #[test]
+    fn pieces_the_peer_has_are_bounded() {
+        let peer = connect_to_peer_sending(Message::Have(9), 10).unwrap();
+        assert!(peer.has_piece(9) && !peer.has_piece(8));
+        assert!(connect_to_peer_sending(Message::Have(10), 10).is_err());
+        assert!(connect_to_peer_sending(Message::Have(u32::MAX), 10).is_err());
+
+        let peer = connect_to_peer_sending(Message::Bitfield(vec![0x80, 0x40]), 10).unwrap();
+        assert!(peer.has_piece(0) && peer.has_piece(9));
+        assert!(connect_to_peer_sending(Message::Bitfield(vec![0; 3]), 10).is_err());
+    }
+}
@@ -0,0 +1,97 @@
+use anyhow::{ensure, Context, Result};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use std::fmt;
+use std::path::Path;
+
+/// The 20 bytes a client identifies itself with to trackers and peers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerId([u8; 20]);
+
+impl PeerId {
+    /// Client code and version in the Azureus style, e.g. "-TT0100-" for 0.1.0
+    pub fn default_prefix() -> String {
+        azureus_prefix(
+            env!("CARGO_PKG_VERSION_MAJOR"),
+            env!("CARGO_PKG_VERSION_MINOR"),
+            env!("CARGO_PKG_VERSION_PATCH"),
+        )
+    }
+
+    /// Fill up `prefix` with random alphanumeric characters
+    pub fn with_prefix(prefix: &str) -> Result<PeerId> {
+        ensure!(
+            prefix.len() <= 20,
+            "peer id prefix {:?} is longer than 20 bytes",
+            prefix
+        );
+        let mut id = [0; 20];
+        id[..prefix.len()].copy_from_slice(prefix.as_bytes());
+        for b in &mut id[prefix.len()..] {
+            *b = thread_rng().sample(Alphanumeric);
+        }
+        Ok(PeerId(id))
+    }
+
+    /// 20 random bytes, which tells others nothing about the client
+    pub fn random() -> PeerId {
+        PeerId(rand::random())
+    }
+
+    /// Reuse the id stored at `path`, or store a new one from `generate` there
+    /// so we keep the same identity across runs
+    pub fn persisted(path: &Path, generate: impl FnOnce() -> Result<PeerId>) -> Result<PeerId> {
+        if path.exists() {
+            let bytes = std::fs::read(path)
+                .with_context(|| format!("could not read peer id from {:#?}", path))?;
+            ensure!(bytes.len() == 20, "{:#?} does not contain a peer id", path);
+            return Ok(PeerId(bytes.try_into().unwrap()));
+        }
+        let id = generate()?;
+        std::fs::write(path, id.0)
+            .with_context(|| format!("could not store peer id in {:#?}", path))?;
+        Ok(id)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 20] {
+        &self.0
+    }
+}
+
+/// The Azureus-style prefix always has 8 bytes, so each version part gets a
+/// single character. Parts above 9 become letters, "A" for 10 up to "Z".
+fn azureus_prefix(major: &str, minor: &str, patch: &str) -> String {
+    let part = |part: &str| {
+        let n: u32 = part.parse().unwrap_or(0);
+        std::char::from_digit(n.min(35), 36)
+            .unwrap()
+            .to_ascii_uppercase()
+    };
+    format!("-TT{}{}{}0-", part(major), part(minor), part(patch))
+}
+
+impl From<[u8; 20]> for PeerId {
+    fn from(bytes: [u8; 20]) -> PeerId {
+        PeerId(bytes)
+    }
+}
+
+/// Printable ASCII is shown as is, everything else escaped
+impl fmt::Display for PeerId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0.escape_ascii())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn azureus_prefix_has_one_character_per_version_part() {
+        assert_eq!(azureus_prefix("0", "1", "0"), "-TT0100-");
+        assert_eq!(azureus_prefix("0", "10", "0"), "-TT0A00-");
+        assert_eq!(azureus_prefix("1", "35", "100"), "-TT1ZZ0-");
+        assert_eq!(PeerId::default_prefix().len(), 8);
+    }
+}
@@ -0,0 +1,36 @@
+use crate::error::Error;
+use anyhow::Result;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Catch Ctrl-C so a download can wind down and tell the tracker it stopped.
+/// A second Ctrl-C exits right away.
+pub fn install_handler() -> Result<()> {
+    ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            std::process::exit(Error::Interrupted.exit_code());
+        }
+        eprintln!("interrupted, shutting down (press Ctrl-C again to force)");
+    })?;
+    Ok(())
+}
+
+/// Fail with `Error::Interrupted` once Ctrl-C was pressed
+pub fn check() -> Result<()> {
+    if INTERRUPTED.load(Ordering::SeqCst) {
+        return Err(Error::Interrupted.into());
+    }
+    Ok(())
+}
+
+/// Like `std::thread::sleep`, but returns early when interrupted
+pub fn sleep(duration: Duration) -> Result<()> {
+    let deadline = Instant::now() + duration;
+    while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+        check()?;
+        std::thread::sleep(left.min(Duration::from_millis(100)));
+    }
+    check()
+}
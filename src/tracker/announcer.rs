@@ -0,0 +1,92 @@
+use super::{Event, TrackerAnnounce, TrackerList, TrackerResponse};
+use crate::shutdown;
+use anyhow::Result;
+use std::time::{Duration, Instant};
+
+/// Used when the tracker doesn't send an interval
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(30 * 60);
+
+/// Lower bound for re-announces if the tracker doesn't send a `min interval`
+const DEFAULT_MIN_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Schedules the announces of a running download.
+///
+/// Periodic announces follow the `interval` of the last response, announces
+/// requested out of schedule wait until `min interval` has passed.
+pub struct Announcer {
+    trackers: TrackerList,
+    template: TrackerAnnounce,
+    interval: Duration,
+    min_interval: Duration,
+    last_announce: Option<Instant>,
+    downloaded: u64,
+    left: u64,
+}
+
+impl Announcer {
+    /// `template` holds everything that doesn't change between announces,
+    /// the transfer statistics and event are filled in for every request.
+    pub fn new(trackers: TrackerList, template: TrackerAnnounce, left: u64) -> Announcer {
+        Announcer {
+            trackers,
+            template,
+            interval: DEFAULT_INTERVAL,
+            min_interval: DEFAULT_MIN_INTERVAL,
+            last_announce: None,
+            downloaded: 0,
+            left,
+        }
+    }
+
+    /// Update the transfer statistics sent with the next announce
+    pub fn set_progress(&mut self, downloaded: u64, left: u64) {
+        self.downloaded = downloaded;
+        self.left = left;
+    }
+
+    pub fn announce(&mut self, event: Option<Event>) -> Result<TrackerResponse> {
+        let mut req = self
+            .template
+            .clone()
+            .downloaded(self.downloaded)
+            .left(self.left);
+        if let Some(event) = event {
+            req = req.event(event);
+        }
+        let res = self.trackers.announce(&req)?;
+        self.last_announce = Some(Instant::now());
+
+        let secs = |s: i64| Duration::from_secs(s.max(0) as u64);
+        self.interval = res.interval.map_or(DEFAULT_INTERVAL, secs);
+        self.min_interval = res.min_interval.map_or(DEFAULT_MIN_INTERVAL, secs);
+        if let Some(warning) = &res.warning_message {
+            eprintln!("tracker warning: {}", warning);
+        }
+        println!(
+            "tracker returned {} peers ({} seeders, {} leechers)",
+            res.peers.len(),
+            res.complete.unwrap_or(0),
+            res.incomplete.unwrap_or(0)
+        );
+        Ok(res)
+    }
+
+    /// Whether the interval requested by the tracker has passed
+    pub fn is_due(&self) -> bool {
+        self.last_announce
+            .is_none_or(|last| last.elapsed() >= self.interval)
+    }
+
+    /// Announce ahead of schedule, e.g. because we ran out of peers.
+    /// Blocks until the `min interval` since the last announce has passed.
+    pub fn reannounce(&mut self) -> Result<TrackerResponse> {
+        if let Some(last) = self.last_announce {
+            let wait = self.min_interval.saturating_sub(last.elapsed());
+            if !wait.is_zero() {
+                println!("waiting {}s before announcing again", wait.as_secs());
+                shutdown::sleep(wait)?;
+            }
+        }
+        self.announce(None)
+    }
+}
@@ -0,0 +1,306 @@
+use super::{ScrapeStats, Tracker, TrackerAnnounce, TrackerConfig, TrackerResponse, TrackerUrl};
+use crate::error::Error;
+use crate::info_hash::InfoHash;
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+use serde_bytes::ByteBuf;
+use std::collections::HashMap;
+
+#[derive(Debug, Deserialize)]
+struct ScrapeResponse {
+    #[serde(default)]
+    #[serde(rename = "failure reason")]
+    failure_reason: Option<String>,
+    #[serde(default)]
+    files: HashMap<ByteBuf, ScrapeStats>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Failure {
+    #[serde(rename = "failure reason")]
+    failure_reason: String,
+}
+
+/// Tracker reached via HTTP(S) GET requests (BEP 3)
+pub struct HttpTracker {
+    url: String,
+    client: reqwest::blocking::Client,
+    /// The `tracker id` of the last response, if the tracker sent one
+    tracker_id: Option<ByteBuf>,
+}
+
+impl HttpTracker {
+    pub fn new(url: &str, config: &TrackerConfig) -> Result<HttpTracker> {
+        let mut builder =
+            reqwest::blocking::Client::builder().danger_accept_invalid_certs(config.insecure);
+        if let Some(proxy) = &config.proxy {
+            builder = builder.proxy(proxy.clone());
+        }
+        for pem in config.ca_certificates()? {
+            let cert = reqwest::Certificate::from_pem(pem.as_bytes())
+                .context("invalid certificate in the CA bundle")?;
+            builder = builder.add_root_certificate(cert);
+        }
+        Ok(HttpTracker {
+            url: url.to_string(),
+            client: builder.build()?,
+            tracker_id: None,
+        })
+    }
+
+    /// GET `url` from the tracker and return the body. Network errors and
+    /// server errors (5xx) mean the tracker is unreachable, client errors
+    /// (4xx) are the tracker rejecting the request.
+    fn get(&self, url: &str) -> Result<Vec<u8>> {
+        let unreachable = || Error::TrackerUnreachable(self.url.clone());
+        let res = self.client.get(url).send().with_context(unreachable)?;
+        let status = res.status();
+        if status.is_server_error() {
+            return Err(anyhow!("tracker responded with {}", status).context(unreachable()));
+        }
+        let body = res.bytes().with_context(unreachable)?;
+        if status.is_client_error() {
+            // Trackers may explain why they rejected the request
+            if let Ok(Failure { failure_reason }) = serde_bencode::from_bytes(&body) {
+                bail!("tracker responded with failure: {}", failure_reason);
+            }
+            bail!("tracker responded with {}", status);
+        }
+        Ok(body.to_vec())
+    }
+
+    /// By convention the scrape URL is the announce URL with the "announce"
+    /// at the start of the last path segment replaced by "scrape". Trackers
+    /// whose URL doesn't follow that pattern don't support scraping.
+    pub fn scrape_url(&self) -> Option<String> {
+        let (path, query) = match self.url.find('?') {
+            Some(i) => self.url.split_at(i),
+            None => (self.url.as_str(), ""),
+        };
+        let segment = path.rfind('/')? + 1;
+        if !path[segment..].starts_with("announce") {
+            return None;
+        }
+        Some(format!(
+            "{}scrape{}{}",
+            &path[..segment],
+            &path[segment + "announce".len()..],
+            query
+        ))
+    }
+}
+
+/// The full announce URL including all query parameters.
+///
+/// The info hash and peer id are raw 20 byte strings and get percent-encoded
+/// byte by byte, any query parameters already present in the announce URL
+/// (like private tracker passkeys) are kept.
+pub fn announce_url(base: &str, req: &TrackerAnnounce) -> String {
+    let mut url = base.to_string();
+    url.push(if url.contains('?') { '&' } else { '?' });
+    url += &format!(
+        "info_hash={}&peer_id={}&port={}&uploaded={}&downloaded={}&left={}",
+        urlencode(&req.info_hash.truncated()),
+        urlencode(req.peer_id.as_bytes()),
+        req.port,
+        req.uploaded,
+        req.downloaded,
+        req.left
+    );
+    if let Some(event) = req.event {
+        url += &format!("&event={}", event.as_str());
+    }
+    if req.compact {
+        url += "&compact=1";
+    }
+    if req.no_peer_id {
+        url += "&no_peer_id=1";
+    }
+    if let Some(numwant) = req.numwant {
+        url += &format!("&numwant={}", numwant);
+    }
+    if let Some(key) = req.key {
+        url += &format!("&key={:08X}", key);
+    }
+    if let Some(trackerid) = &req.trackerid {
+        url += &format!("&trackerid={}", urlencode(trackerid));
+    }
+    if let Some(ip) = &req.ip {
+        url += &format!("&ip={}", urlencode(ip.as_bytes()));
+    }
+    if let Some(ipv6) = &req.ipv6 {
+        url += &format!("&ipv6={}", urlencode(ipv6.to_string().as_bytes()));
+    }
+    url
+}
+
+/// The announce URL and its decoded query parameters, for showing to humans
+pub fn describe_announce(base: &str, req: &TrackerAnnounce) -> (String, Vec<(String, String)>) {
+    let url = announce_url(base, req);
+    let query = url.split_once('?').map_or("", |(_, query)| query);
+    let params = query
+        .split('&')
+        .map(|param| {
+            let (name, value) = param.split_once('=').unwrap_or((param, ""));
+            let value = percent_decode(value);
+            let value = if value.iter().all(u8::is_ascii_graphic) {
+                String::from_utf8_lossy(&value).into_owned()
+            } else {
+                format!("{} (hex)", hex::encode(&value))
+            };
+            (name.to_string(), value)
+        })
+        .collect();
+    (url, params)
+}
+
+impl Tracker for HttpTracker {
+    fn supports_url(url: &TrackerUrl) -> bool {
+        matches!(url, TrackerUrl::Http(_) | TrackerUrl::Https(_))
+    }
+
+    fn announce(&mut self, req: &TrackerAnnounce) -> Result<TrackerResponse> {
+        let url = match (&req.trackerid, &self.tracker_id) {
+            (None, Some(id)) => announce_url(&self.url, &req.clone().trackerid(id)),
+            _ => announce_url(&self.url, req),
+        };
+        let res = TrackerResponse::from_bytes(&self.get(&url)?)?;
+        if res.tracker_id.is_some() {
+            self.tracker_id = res.tracker_id.clone();
+        }
+        Ok(res)
+    }
+
+    fn scrape(&mut self, info_hash: &InfoHash) -> Result<ScrapeStats> {
+        let mut url = self
+            .scrape_url()
+            .ok_or(anyhow!("tracker {} does not support scraping", self.url))?;
+        url.push(if url.contains('?') { '&' } else { '?' });
+        url += &format!("info_hash={}", urlencode(&info_hash.truncated()));
+        let mut res: ScrapeResponse = serde_bencode::from_bytes(&self.get(&url)?)
+            .context("failed to parse the scrape response")?;
+        if let Some(reason) = res.failure_reason {
+            bail!("tracker responded with failure: {}", reason);
+        }
+        res.files
+            .remove(&ByteBuf::from(info_hash.truncated().to_vec()))
+            .ok_or(anyhow!("tracker does not know this torrent"))
+    }
+}
+
+/// Percent-encode every byte except the unreserved characters of RFC 3986
+fn urlencode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len() * 3);
+    for &b in bytes {
+        if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+            encoded.push(b as char);
+        } else {
+            encoded += &format!("%{:02X}", b);
+        }
+    }
+    encoded
+}
+
+/// Undo `urlencode`, invalid escapes are kept as they are
+fn percent_decode(s: &str) -> Vec<u8> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(b) => {
+                decoded.push(b);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peer_id::PeerId;
+    use crate::tracker::Event;
+
+    fn request() -> TrackerAnnounce {
+        TrackerAnnounce::new(InfoHash::V1([0xab; 20]), PeerId::from([b'x'; 20]))
+    }
+
+    #[test]
+    fn announce_url_keeps_binary_values_and_the_passkey() {
+        let req = TrackerAnnounce::new(
+            InfoHash::V1([
+                0, 0xff, b'a', b' ', b'%', 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+            ]),
+            PeerId::from(*b"-TT0100-abc~def.ghi_"),
+        )
+        .left(42)
+        .event(Event::Started);
+        let url = announce_url("http://t/announce?passkey=s3cr%2Ft", &req);
+        assert_eq!(
+            url,
+            "http://t/announce?passkey=s3cr%2Ft\
+             &info_hash=%00%FFa%20%25%01%02%03%04%05%06%07%08%09%0A%0B%0C%0D%0E%0F\
+             &peer_id=-TT0100-abc~def.ghi_&port=6881&uploaded=0&downloaded=0&left=42&event=started"
+        );
+        let (_, params) = describe_announce("http://t/announce?passkey=s3cr%2Ft", &req);
+        assert_eq!(params[0], ("passkey".to_string(), "s3cr/t".to_string()));
+        assert_eq!(
+            params[1].1,
+            "00ff6120250102030405060708090a0b0c0d0e0f (hex)"
+        );
+    }
+
+    #[test]
+    fn percent_decode_undoes_urlencode() {
+        let bytes: Vec<u8> = (0..=255).collect();
+        assert_eq!(percent_decode(&urlencode(&bytes)), bytes);
+        // Invalid escapes are kept
+        assert_eq!(percent_decode("100%&%zz%4"), b"100%&%zz%4");
+    }
+
+    #[test]
+    fn scrape_url_replaces_announce_in_the_last_segment() {
+        let scrape_url = |url| {
+            HttpTracker::new(url, &TrackerConfig::default())
+                .unwrap()
+                .scrape_url()
+        };
+        let cases = [
+            ("http://t/announce", Some("http://t/scrape")),
+            (
+                "http://t/x/announce.php?pk=1",
+                Some("http://t/x/scrape.php?pk=1"),
+            ),
+            ("http://t/announce/x", None),
+            ("http://t/a", None),
+        ];
+        for (url, expected) in cases {
+            assert_eq!(scrape_url(url).as_deref(), expected, "{}", url);
+        }
+    }
+
+    #[test]
+    fn announce_url_contains_our_ipv6_address() {
+        let url = announce_url(
+            "http://t/announce",
+            &request().ipv6("2001:db8::1".parse().unwrap()),
+        );
+        assert!(url.ends_with("&ipv6=2001%3Adb8%3A%3A1"), "{}", url);
+    }
+
+    #[test]
+    fn announce_url_echoes_the_raw_tracker_id() {
+        let url = announce_url("http://t/announce", &request().trackerid(&[0xff, 0, b'a']));
+        assert!(url.ends_with("&trackerid=%FF%00a"), "{}", url);
+    }
+}
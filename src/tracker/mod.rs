@@ -0,0 +1,842 @@
+use crate::error::Error;
+use crate::info_hash::InfoHash;
+use crate::peer_id::PeerId;
+use crate::shutdown;
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::{Deserialize, Deserializer};
+use serde_bytes::ByteBuf;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::path::PathBuf;
+use std::time::Duration;
+
+mod announcer;
+mod http;
+mod udp;
+mod url;
+mod ws;
+
+pub use announcer::Announcer;
+pub use url::TrackerUrl;
+
+/// How much of a response that can't be parsed is shown in the error
+const MAX_RAW_RESPONSE: usize = 1024;
+
+/// A way of talking to a tracker, picked by the scheme of its announce URL
+pub trait Tracker {
+    /// Whether this transport can talk to the tracker at `url`
+    fn supports_url(url: &TrackerUrl) -> bool
+    where
+        Self: Sized;
+    fn announce(&mut self, req: &TrackerAnnounce) -> Result<TrackerResponse>;
+    fn scrape(&mut self, info_hash: &InfoHash) -> Result<ScrapeStats>;
+}
+
+type Connect = fn(&TrackerUrl, &TrackerConfig) -> Result<Box<dyn Tracker>>;
+
+#[derive(Debug, Clone)]
+struct Transport {
+    supports_url: fn(&TrackerUrl) -> bool,
+    connect: Connect,
+}
+
+/// The tracker transports we know, used to create the client for a URL
+#[derive(Debug, Clone)]
+pub struct TrackerRegistry {
+    transports: Vec<Transport>,
+}
+
+impl Default for TrackerRegistry {
+    fn default() -> TrackerRegistry {
+        let mut registry = TrackerRegistry {
+            transports: Vec::new(),
+        };
+        registry.register(http::HttpTracker::supports_url, |url, config| {
+            Ok(Box::new(http::HttpTracker::new(url.as_str(), config)?))
+        });
+        registry.register(udp::UdpTracker::supports_url, |url, config| {
+            ensure!(
+                config.proxy.is_none(),
+                "UDP trackers can't be used with a proxy"
+            );
+            Ok(Box::new(udp::UdpTracker::new(url.as_str())?))
+        });
+        registry.register(ws::WsTracker::supports_url, |url, config| {
+            ensure!(
+                config.proxy.is_none(),
+                "WebSocket trackers can't be used with a proxy"
+            );
+            Ok(Box::new(ws::WsTracker::new(url.as_str(), config)?))
+        });
+        registry
+    }
+}
+
+impl TrackerRegistry {
+    /// Add a transport. It takes precedence over the ones registered before,
+    /// so built-in transports can be replaced, e.g. by a mock.
+    pub fn register(&mut self, supports_url: fn(&TrackerUrl) -> bool, connect: Connect) {
+        self.transports.push(Transport {
+            supports_url,
+            connect,
+        });
+    }
+
+    /// Create the tracker client for `url`
+    pub fn connect(&self, url: &TrackerUrl, config: &TrackerConfig) -> Result<Box<dyn Tracker>> {
+        match self.transports.iter().rev().find(|t| (t.supports_url)(url)) {
+            Some(transport) => (transport.connect)(url, config),
+            None => bail!("unsupported tracker protocol in {}", url),
+        }
+    }
+}
+
+/// Settings that apply to all tracker connections
+#[derive(Debug, Clone, Default)]
+pub struct TrackerConfig {
+    /// PEM file with additional root certificates for HTTPS trackers
+    pub ca_bundle: Option<PathBuf>,
+    /// Accept invalid certificates from HTTPS trackers
+    pub insecure: bool,
+    /// Send all tracker requests through this proxy. Trackers that can't be
+    /// reached through it are skipped instead of leaking our address.
+    pub proxy: Option<reqwest::Proxy>,
+    pub retry: RetryPolicy,
+    pub registry: TrackerRegistry,
+}
+
+impl TrackerConfig {
+    /// The certificates of the CA bundle in PEM format, one per element
+    fn ca_certificates(&self) -> Result<Vec<String>> {
+        let path = match &self.ca_bundle {
+            Some(path) => path,
+            None => return Ok(Vec::new()),
+        };
+        let pem = std::fs::read_to_string(path)
+            .with_context(|| format!("could not read CA bundle {:#?}", path))?;
+        let certs: Vec<String> = pem
+            .split_inclusive("-----END CERTIFICATE-----")
+            .filter(|block| block.contains("-----BEGIN CERTIFICATE-----"))
+            .map(str::to_string)
+            .collect();
+        ensure!(!certs.is_empty(), "no certificates found in {:#?}", path);
+        Ok(certs)
+    }
+
+    /// Our global IPv6 address to put into announces. Not sent when going
+    /// through a proxy, which is there to hide our address from trackers.
+    pub fn announced_ipv6(&self) -> Option<Ipv6Addr> {
+        match self.proxy {
+            Some(_) => None,
+            None => local_ipv6(),
+        }
+    }
+}
+
+/// How often a tracker that can't be reached is retried before falling back
+/// to the next one. Failure responses from the tracker are never retried.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub retries: u32,
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            retries: 2,
+            initial_delay: Duration::from_secs(2),
+            max_delay: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The delay doubles with every attempt, randomized by +-50% so clients
+    /// that failed together don't all come back at the same time
+    fn delay(&self, attempt: u32) -> Duration {
+        let delay = self
+            .initial_delay
+            .saturating_mul(1 << attempt.min(16))
+            .min(self.max_delay);
+        delay.mul_f64(rand::thread_rng().gen_range(0.5..1.5))
+    }
+}
+
+/// Network errors are worth retrying, everything else won't go away by itself
+fn is_transient(err: &anyhow::Error) -> bool {
+    matches!(
+        err.downcast_ref::<Error>(),
+        Some(Error::TrackerUnreachable(_))
+    )
+}
+
+struct TierEntry {
+    url: TrackerUrl,
+    client: Option<Box<dyn Tracker>>,
+}
+
+/// All trackers of a torrent, grouped into tiers as described in BEP 12
+pub struct TrackerList {
+    tiers: Vec<Vec<TierEntry>>,
+    config: TrackerConfig,
+}
+
+/// The usable tracker URLs of a torrent, grouped into tiers. `announce-list`
+/// takes precedence over `announce` if it is present (BEP 12).
+///
+/// Entries that aren't valid URLs of a tracker protocol we speak are skipped
+/// with a warning, so a single broken entry doesn't make the torrent unusable.
+pub fn tracker_urls(
+    announce: Option<&str>,
+    announce_list: Option<&[Vec<String>]>,
+) -> Result<Vec<Vec<TrackerUrl>>> {
+    let parse = |url: &str| match url.parse() {
+        Ok(url) => Some(url),
+        Err(e) => {
+            eprintln!("skipping tracker: {:#}", e);
+            None
+        }
+    };
+    let mut tiers: Vec<Vec<TrackerUrl>> = announce_list
+        .unwrap_or_default()
+        .iter()
+        .map(|tier| tier.iter().filter_map(|url| parse(url)).collect())
+        .filter(|tier: &Vec<TrackerUrl>| !tier.is_empty())
+        .collect();
+    if tiers.is_empty() {
+        let url = announce.ok_or(anyhow!("Expected value for 'announce'"))?;
+        tiers.extend(parse(url).map(|url| vec![url]));
+    }
+    ensure!(!tiers.is_empty(), "the torrent has no usable tracker");
+    Ok(tiers)
+}
+
+impl TrackerList {
+    /// The trackers within each tier are shuffled.
+    ///
+    /// WebSocket trackers can't give us peers we are able to connect to, so
+    /// they are moved behind the other trackers of hybrid torrents.
+    pub fn new(
+        announce: Option<&str>,
+        announce_list: Option<&[Vec<String>]>,
+        config: TrackerConfig,
+    ) -> Result<TrackerList> {
+        let mut tiers: Vec<Vec<TierEntry>> = tracker_urls(announce, announce_list)?
+            .into_iter()
+            .map(|tier| {
+                let mut tier: Vec<TierEntry> = tier
+                    .into_iter()
+                    .map(|url| TierEntry { url, client: None })
+                    .collect();
+                tier.shuffle(&mut rand::thread_rng());
+                tier.sort_by_key(|entry| ws::WsTracker::supports_url(&entry.url));
+                tier
+            })
+            .collect();
+        tiers.sort_by_key(|tier| {
+            tier.iter()
+                .all(|entry| ws::WsTracker::supports_url(&entry.url))
+        });
+        Ok(TrackerList { tiers, config })
+    }
+
+    /// Run `f` against the trackers in order until one of them succeeds,
+    /// retrying unreachable trackers according to the retry policy first.
+    /// The tracker that responded is moved to the front of its tier.
+    fn first_responding<T>(
+        &mut self,
+        action: &str,
+        mut f: impl FnMut(&mut dyn Tracker) -> Result<T>,
+    ) -> Result<T> {
+        let mut last_err = None;
+        for tier in self.tiers.iter_mut() {
+            for i in 0..tier.len() {
+                let entry = &mut tier[i];
+                println!("{} {}", action, entry.url);
+                if entry.client.is_none() {
+                    match self.config.registry.connect(&entry.url, &self.config) {
+                        Ok(client) => entry.client = Some(client),
+                        Err(e) => {
+                            eprintln!("skipping {}: {:#}", entry.url, e);
+                            last_err = Some(e);
+                            continue;
+                        }
+                    }
+                }
+                let client = entry.client.as_deref_mut().unwrap();
+                let mut attempt = 0;
+                let res = loop {
+                    match f(client) {
+                        Err(e) if attempt < self.config.retry.retries && is_transient(&e) => {
+                            let delay = self.config.retry.delay(attempt);
+                            eprintln!(
+                                "{} failed: {:#}, retrying in {:.1}s",
+                                entry.url,
+                                e,
+                                delay.as_secs_f32()
+                            );
+                            shutdown::sleep(delay)?;
+                            attempt += 1;
+                        }
+                        res => break res,
+                    }
+                };
+                match res {
+                    Ok(res) => {
+                        let entry = tier.remove(i);
+                        tier.insert(0, entry);
+                        return Ok(res);
+                    }
+                    Err(e) => {
+                        eprintln!("{} failed: {:#}", entry.url, e);
+                        last_err = Some(e);
+                    }
+                }
+            }
+        }
+        Err(last_err.unwrap().context("every tracker failed"))
+    }
+
+    pub fn announce(&mut self, req: &TrackerAnnounce) -> Result<TrackerResponse> {
+        self.first_responding("announcing to", |tracker| tracker.announce(req))
+    }
+
+    pub fn scrape(&mut self, info_hash: &InfoHash) -> Result<ScrapeStats> {
+        self.first_responding("scraping", |tracker| tracker.scrape(info_hash))
+    }
+}
+
+/// Show what an announce to `url` looks like and, unless `dry_run` is set,
+/// what the tracker answers. A successful announce is followed by a
+/// `stopped` one with the same client, so we don't stay in the swarm.
+pub fn debug_announce(
+    url: &TrackerUrl,
+    req: &TrackerAnnounce,
+    config: &TrackerConfig,
+    dry_run: bool,
+) -> Result<()> {
+    let fields = match url {
+        TrackerUrl::Http(_) | TrackerUrl::Https(_) => {
+            let (url, fields) = http::describe_announce(url.as_str(), req);
+            println!("{}", url);
+            fields
+        }
+        TrackerUrl::Udp(_) => {
+            println!("{}", url);
+            udp::describe_announce(req)
+        }
+        TrackerUrl::Ws(_) | TrackerUrl::Wss(_) => {
+            println!("{}", url);
+            ws::describe_announce(req)
+        }
+    };
+    for (name, value) in fields {
+        println!("  {:<16}{}", name, value);
+    }
+    if dry_run {
+        return Ok(());
+    }
+
+    let mut tracker = config.registry.connect(url, config)?;
+    let res = tracker.announce(req)?;
+    let fields = [
+        ("warning message", res.warning_message.clone()),
+        ("interval", res.interval.map(|i| i.to_string())),
+        ("min interval", res.min_interval.map(|i| i.to_string())),
+        (
+            "tracker id",
+            res.tracker_id
+                .as_ref()
+                .map(|id| id.escape_ascii().to_string()),
+        ),
+        ("seeders", res.complete.map(|n| n.to_string())),
+        ("leechers", res.incomplete.map(|n| n.to_string())),
+    ];
+    println!("response:");
+    for (name, value) in fields {
+        if let Some(value) = value {
+            println!("  {:<16}{}", name, value);
+        }
+    }
+    println!("  {:<16}{}", "peers", res.peers.len());
+    for peer in &res.peers {
+        println!("    {}", peer);
+    }
+
+    if let Err(e) = tracker.announce(&req.clone().event(Event::Stopped)) {
+        println!("could not leave the swarm again: {:#}", e);
+    }
+    Ok(())
+}
+
+/// The global IPv6 address we would reach the internet from, if we have one.
+///
+/// Connecting a UDP socket only picks the route and source address, nothing
+/// is sent.
+fn local_ipv6() -> Option<Ipv6Addr> {
+    let socket = UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0)).ok()?;
+    socket.connect("[2001:4860:4860::8888]:53").ok()?;
+    match socket.local_addr().ok()?.ip() {
+        IpAddr::V6(ip) if is_global(&ip) => Some(ip),
+        _ => None,
+    }
+}
+
+/// Excludes the loopback, link-local and unique local ranges
+fn is_global(ip: &Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    !ip.is_loopback()
+        && !ip.is_unspecified()
+        && first & 0xffc0 != 0xfe80
+        && first & 0xfe00 != 0xfc00
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    Started,
+    Completed,
+    Stopped,
+}
+
+impl Event {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Event::Started => "started",
+            Event::Completed => "completed",
+            Event::Stopped => "stopped",
+        }
+    }
+}
+
+/// The parameters of an announce, independent of the tracker protocol
+#[derive(Debug, Clone)]
+pub struct TrackerAnnounce {
+    info_hash: InfoHash,
+    peer_id: PeerId,
+    port: u16,
+    uploaded: u64,
+    downloaded: u64,
+    left: u64,
+    event: Option<Event>,
+    compact: bool,
+    no_peer_id: bool,
+    numwant: Option<u32>,
+    key: Option<u32>,
+    trackerid: Option<ByteBuf>,
+    ip: Option<String>,
+    /// Our IPv6 address, so trackers reached over IPv4 can hand it out too (BEP 7)
+    ipv6: Option<Ipv6Addr>,
+}
+
+#[allow(dead_code)]
+impl TrackerAnnounce {
+    pub fn new(info_hash: InfoHash, peer_id: PeerId) -> TrackerAnnounce {
+        TrackerAnnounce {
+            info_hash,
+            peer_id,
+            port: 6881,
+            uploaded: 0,
+            downloaded: 0,
+            left: 0,
+            event: None,
+            compact: false,
+            no_peer_id: false,
+            numwant: None,
+            key: None,
+            trackerid: None,
+            ip: None,
+            ipv6: None,
+        }
+    }
+
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    pub fn uploaded(mut self, uploaded: u64) -> Self {
+        self.uploaded = uploaded;
+        self
+    }
+
+    pub fn downloaded(mut self, downloaded: u64) -> Self {
+        self.downloaded = downloaded;
+        self
+    }
+
+    pub fn left(mut self, left: u64) -> Self {
+        self.left = left;
+        self
+    }
+
+    pub fn event(mut self, event: Event) -> Self {
+        self.event = Some(event);
+        self
+    }
+
+    pub fn compact(mut self, compact: bool) -> Self {
+        self.compact = compact;
+        self
+    }
+
+    pub fn no_peer_id(mut self, no_peer_id: bool) -> Self {
+        self.no_peer_id = no_peer_id;
+        self
+    }
+
+    pub fn numwant(mut self, numwant: u32) -> Self {
+        self.numwant = Some(numwant);
+        self
+    }
+
+    pub fn key(mut self, key: u32) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    pub fn trackerid(mut self, trackerid: &[u8]) -> Self {
+        self.trackerid = Some(ByteBuf::from(trackerid));
+        self
+    }
+
+    pub fn ip(mut self, ip: &str) -> Self {
+        self.ip = Some(ip.to_string());
+        self
+    }
+
+    pub fn ipv6(mut self, ipv6: Ipv6Addr) -> Self {
+        self.ipv6 = Some(ipv6);
+        self
+    }
+}
+
+/// The bencoded dictionary a tracker answers an announce with
+#[derive(Debug, Default, Deserialize)]
+#[allow(dead_code)]
+pub struct TrackerResponse {
+    #[serde(default)]
+    #[serde(rename = "failure reason")]
+    pub failure_reason: Option<String>,
+    #[serde(default)]
+    #[serde(rename = "warning message")]
+    pub warning_message: Option<String>,
+    #[serde(default)]
+    pub interval: Option<i64>,
+    #[serde(default)]
+    #[serde(rename = "min interval")]
+    pub min_interval: Option<i64>,
+    /// Has to be sent back with every following announce to the same tracker.
+    /// Nothing says it is text, so it is kept as the raw bytes.
+    #[serde(default)]
+    #[serde(rename = "tracker id")]
+    pub tracker_id: Option<ByteBuf>,
+    /// Number of seeders
+    #[serde(default)]
+    pub complete: Option<i64>,
+    /// Number of leechers
+    #[serde(default)]
+    pub incomplete: Option<i64>,
+    /// IPv4 and IPv6 peers
+    #[serde(default)]
+    #[serde(deserialize_with = "deserialize_peers")]
+    pub peers: Vec<SocketAddr>,
+    /// IPv6 peers in the compact format (BEP 7), merged into `peers` by `from_bytes`
+    #[serde(default)]
+    #[serde(deserialize_with = "deserialize_peers6")]
+    peers6: Vec<SocketAddr>,
+}
+
+impl TrackerResponse {
+    /// Parse the body of an announce response, turning a `failure reason` into an error
+    pub fn from_bytes(buf: &[u8]) -> Result<TrackerResponse> {
+        let mut res: TrackerResponse = serde_bencode::from_bytes(buf).with_context(|| {
+            // Show what we got, it's often an HTML error page or a typo in a custom tracker
+            let raw = &buf[..buf.len().min(MAX_RAW_RESPONSE)];
+            format!(
+                "failed to parse the tracker response {}",
+                raw.escape_ascii()
+            )
+        })?;
+        if let Some(reason) = &res.failure_reason {
+            bail!("tracker responded with failure: {}", reason);
+        }
+        res.peers.append(&mut res.peers6);
+        Ok(res)
+    }
+}
+
+/// Swarm statistics for a single torrent as reported by a scrape
+#[derive(Debug, Default, Deserialize)]
+pub struct ScrapeStats {
+    /// Number of seeders
+    #[serde(default)]
+    pub complete: i64,
+    /// Number of times the download was completed
+    #[serde(default)]
+    pub downloaded: i64,
+    /// Number of leechers
+    #[serde(default)]
+    pub incomplete: i64,
+}
+
+/// Peers in the original dictionary model
+#[derive(Debug, Deserialize)]
+struct DictPeer {
+    ip: String,
+    port: u16,
+}
+
+/// Decode peers in the compact format, 6 bytes (ip + port) per peer
+fn compact_peers(buf: &[u8]) -> Vec<SocketAddr> {
+    buf.chunks_exact(6)
+        .map(|c| {
+            let ip = Ipv4Addr::new(c[0], c[1], c[2], c[3]);
+            SocketAddr::from((ip, u16::from_be_bytes([c[4], c[5]])))
+        })
+        .collect()
+}
+
+/// Decode IPv6 peers in the compact format, 18 bytes (ip + port) per peer
+fn compact_peers6(buf: &[u8]) -> Vec<SocketAddr> {
+    buf.chunks_exact(18)
+        .map(|c| {
+            let ip: [u8; 16] = c[..16].try_into().unwrap();
+            SocketAddr::from((Ipv6Addr::from(ip), u16::from_be_bytes([c[16], c[17]])))
+        })
+        .collect()
+}
+
+/// Trackers either send a list of dictionaries or, when `compact=1` was
+/// requested, a single string in the compact format.
+fn deserialize_peers<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<SocketAddr>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Peers {
+        Compact(ByteBuf),
+        Dict(Vec<DictPeer>),
+    }
+
+    let peers = match Peers::deserialize(deserializer)? {
+        Peers::Compact(buf) => compact_peers(&buf),
+        // The ip may also be a DNS name, those peers are skipped
+        Peers::Dict(peers) => peers
+            .into_iter()
+            .filter_map(|p| Some(SocketAddr::new(p.ip.parse::<IpAddr>().ok()?, p.port)))
+            .collect(),
+    };
+    Ok(peers)
+}
+
+fn deserialize_peers6<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<SocketAddr>, D::Error> {
+    Ok(compact_peers6(&ByteBuf::deserialize(deserializer)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+
+    thread_local! {
+        /// Number of announces each mock tracker got, by host
+        static CALLS: RefCell<HashMap<String, u32>> = RefCell::new(HashMap::new());
+    }
+
+    fn calls(host: &str) -> u32 {
+        CALLS.with(|calls| calls.borrow().get(host).copied().unwrap_or(0))
+    }
+
+    /// Behaves according to the host of its URL: "ok" responds, "dead" is
+    /// unreachable, "flaky" is unreachable twice and "rejects" sends a failure
+    struct MockTracker {
+        host: String,
+    }
+
+    impl Tracker for MockTracker {
+        fn supports_url(url: &TrackerUrl) -> bool {
+            matches!(url, TrackerUrl::Http(_))
+        }
+
+        fn announce(&mut self, _req: &TrackerAnnounce) -> Result<TrackerResponse> {
+            let n = CALLS.with(|calls| {
+                let mut calls = calls.borrow_mut();
+                let n = calls.entry(self.host.clone()).or_default();
+                *n += 1;
+                *n
+            });
+            match self.host.as_str() {
+                "ok" => {}
+                "flaky" if n > 2 => {}
+                "rejects" => bail!("tracker responded with failure: unregistered torrent"),
+                _ => return Err(Error::TrackerUnreachable(self.host.clone()).into()),
+            }
+            Ok(TrackerResponse {
+                interval: Some(1800),
+                ..Default::default()
+            })
+        }
+
+        fn scrape(&mut self, _info_hash: &InfoHash) -> Result<ScrapeStats> {
+            bail!("not supported by the mock")
+        }
+    }
+
+    fn trackers(tiers: &[&[&str]]) -> TrackerList {
+        let mut config = TrackerConfig {
+            retry: RetryPolicy {
+                retries: 2,
+                initial_delay: Duration::ZERO,
+                max_delay: Duration::ZERO,
+            },
+            ..Default::default()
+        };
+        config
+            .registry
+            .register(MockTracker::supports_url, |url, _config| {
+                let host = url.as_str().split('/').nth(2).unwrap().to_string();
+                Ok(Box::new(MockTracker { host }))
+            });
+        let tiers: Vec<Vec<String>> = tiers
+            .iter()
+            .map(|tier| {
+                tier.iter()
+                    .map(|host| format!("http://{}/announce", host))
+                    .collect()
+            })
+            .collect();
+        TrackerList::new(None, Some(&tiers), config).unwrap()
+    }
+
+    fn announce(trackers: &mut TrackerList) -> Result<TrackerResponse> {
+        trackers.announce(&TrackerAnnounce::new(
+            InfoHash::V1([0; 20]),
+            PeerId::from([0; 20]),
+        ))
+    }
+
+    #[test]
+    fn responding_tracker_is_promoted_within_its_tier() {
+        let mut list = trackers(&[&["dead", "ok"]]);
+        announce(&mut list).unwrap();
+        assert_eq!(list.tiers[0][0].url.as_str(), "http://ok/announce");
+        // Depending on the shuffle the dead tracker was tried first, but not again
+        let dead = calls("dead");
+        assert!(dead == 0 || dead == 3);
+        announce(&mut list).unwrap();
+        assert_eq!((calls("dead"), calls("ok")), (dead, 2));
+    }
+
+    #[test]
+    fn unreachable_trackers_are_retried() {
+        announce(&mut trackers(&[&["flaky"]])).unwrap();
+        assert_eq!(calls("flaky"), 3);
+
+        let err = announce(&mut trackers(&[&["dead"]])).unwrap_err();
+        assert_eq!(calls("dead"), 3);
+        assert_eq!(crate::error::exit_code(&err), 3);
+    }
+
+    #[test]
+    fn failures_fall_through_to_the_next_tier_without_retries() {
+        announce(&mut trackers(&[&["rejects"], &["ok"]])).unwrap();
+        assert_eq!((calls("rejects"), calls("ok")), (1, 1));
+    }
+
+    #[test]
+    fn unusable_urls_are_skipped() {
+        let tiers = vec![
+            vec!["dht://abc".to_string(), "not a url".to_string()],
+            vec!["udp://tracker:80".to_string()],
+        ];
+        let urls = tracker_urls(Some("http://fallback/announce"), Some(&tiers)).unwrap();
+        assert_eq!(urls, vec![vec!["udp://tracker:80".parse().unwrap()]]);
+
+        let tiers = vec![vec!["dht://abc".to_string()]];
+        let urls = tracker_urls(Some("http://fallback/announce"), Some(&tiers)).unwrap();
+        assert_eq!(urls[0][0].as_str(), "http://fallback/announce");
+        assert!(tracker_urls(Some("dht://abc"), Some(&tiers)).is_err());
+    }
+
+    #[test]
+    fn peers_are_parsed_from_dicts() {
+        let res = TrackerResponse::from_bytes(
+            b"d8:intervali1800e5:peersld2:ip9:127.0.0.14:porti6881eed2:ip3:::14:porti6882eed2:ip11:example.org4:porti1eeee",
+        )
+        .unwrap();
+        assert_eq!(res.interval, Some(1800));
+        assert_eq!(
+            res.peers,
+            vec![
+                "127.0.0.1:6881".parse().unwrap(),
+                "[::1]:6882".parse().unwrap()
+            ]
+        );
+    }
+
+    #[test]
+    fn compact_peers_are_merged_with_peers6() {
+        let mut buf = b"d5:peers12:".to_vec();
+        buf.extend_from_slice(&[127, 0, 0, 1, 0x1a, 0xe1, 10, 0, 0, 2, 0, 80]);
+        buf.extend_from_slice(b"6:peers618:");
+        buf.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        buf.extend_from_slice(&[0x1a, 0xe2]);
+        buf.extend_from_slice(b"10:tracker id3:abce");
+        let res = TrackerResponse::from_bytes(&buf).unwrap();
+        assert_eq!(
+            res.tracker_id.as_deref().map(Vec::as_slice),
+            Some(&b"abc"[..])
+        );
+        assert_eq!(
+            res.peers,
+            vec![
+                "127.0.0.1:6881".parse().unwrap(),
+                "10.0.0.2:80".parse().unwrap(),
+                "[::1]:6882".parse().unwrap(),
+            ]
+        );
+        // Incomplete trailing entries are ignored
+        assert_eq!(compact_peers(&[1, 2, 3, 4, 5, 6, 7]).len(), 1);
+        assert_eq!(compact_peers6(&[0; 35]).len(), 1);
+    }
+
+    #[test]
+    fn tracker_ids_do_not_have_to_be_text() {
+        let res = TrackerResponse::from_bytes(b"d10:tracker id2:\xff\x00e").unwrap();
+        assert_eq!(
+            res.tracker_id.as_deref().map(Vec::as_slice),
+            Some(&[0xff, 0][..])
+        );
+    }
+
+    #[test]
+    fn failure_reasons_and_garbage_are_errors() {
+        let err = TrackerResponse::from_bytes(b"d14:failure reason7:go awaye").unwrap_err();
+        assert_eq!(err.to_string(), "tracker responded with failure: go away");
+        let err = TrackerResponse::from_bytes(b"<html>").unwrap_err();
+        assert!(err.to_string().contains("<html>"), "{}", err);
+    }
+
+    #[test]
+    fn only_global_ipv6_addresses_are_announced() {
+        for ip in ["::1", "::", "fe80::1", "fd00::2", "fc00::1"] {
+            assert!(!is_global(&ip.parse().unwrap()), "{}", ip);
+        }
+        assert!(is_global(&"2001:db8::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn no_ipv6_address_is_announced_through_a_proxy() {
+        let config = TrackerConfig {
+            proxy: Some(reqwest::Proxy::all("socks5h://127.0.0.1:9050").unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(config.announced_ipv6(), None);
+    }
+}
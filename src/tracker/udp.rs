@@ -0,0 +1,315 @@
+use super::{
+    compact_peers, compact_peers6, Event, ScrapeStats, Tracker, TrackerAnnounce, TrackerResponse,
+    TrackerUrl,
+};
+use crate::error::Error;
+use crate::info_hash::InfoHash;
+use anyhow::{bail, ensure, Context, Result};
+use rand::random;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// Magic constant identifying the connect request
+const PROTOCOL_ID: u64 = 0x41727101980;
+
+const ACTION_CONNECT: u32 = 0;
+const ACTION_ANNOUNCE: u32 = 1;
+const ACTION_SCRAPE: u32 = 2;
+const ACTION_ERROR: u32 = 3;
+
+/// How long to wait for a response. BEP 15 retransmits after 15 * 2^n
+/// seconds, we send a request once and leave further attempts to the retry
+/// policy of the tracker list, so the two don't add up.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// How long a connection id may be reused for
+const CONNECTION_ID_LIFETIME: Duration = Duration::from_secs(60);
+
+/// Tracker reached via the UDP tracker protocol (BEP 15)
+pub struct UdpTracker {
+    url: String,
+    /// All addresses of the tracker, the ones of the preferred family first
+    addrs: Vec<SocketAddr>,
+    /// The address we currently talk to
+    addr: SocketAddr,
+    socket: UdpSocket,
+    connection: Option<(u64, Instant)>,
+}
+
+/// A socket to send from to `addr`, of the same address family
+fn bind(addr: SocketAddr) -> Result<UdpSocket> {
+    let bind: SocketAddr = if addr.is_ipv4() {
+        (Ipv4Addr::UNSPECIFIED, 0).into()
+    } else {
+        "[::]:0".parse().unwrap()
+    };
+    Ok(UdpSocket::bind(bind)?)
+}
+
+impl UdpTracker {
+    pub fn new(url: &str) -> Result<UdpTracker> {
+        let parsed = reqwest::Url::parse(url).with_context(|| format!("invalid url {}", url))?;
+        ensure!(parsed.port().is_some(), "{} does not contain a port", url);
+        // Resolves names and takes IP addresses, IPv6 ones in brackets, as they are
+        let mut addrs = parsed
+            .socket_addrs(|| None)
+            .with_context(|| Error::TrackerUnreachable(url.to_string()))?;
+        // Announce over IPv6 if we can, the tracker then hands out IPv6 peers
+        let ipv6 = super::local_ipv6().is_some();
+        addrs.sort_by_key(|addr| addr.is_ipv6() != ipv6);
+        let addr = *addrs
+            .first()
+            .ok_or_else(|| Error::TrackerUnreachable(url.to_string()))?;
+        Ok(UdpTracker {
+            url: url.to_string(),
+            addrs,
+            addr,
+            socket: bind(addr)?,
+            connection: None,
+        })
+    }
+
+    /// Make `request` to the current address and, as long as the tracker
+    /// can't be reached there, to each of its other addresses. A tracker
+    /// with a broken IPv6 setup is then still reached over IPv4.
+    fn with_fallback<T>(&mut self, request: impl Fn(&mut Self) -> Result<T>) -> Result<T> {
+        let mut attempts = self.addrs.len();
+        loop {
+            match request(self) {
+                Err(e) if attempts > 1 && super::is_transient(&e) => {
+                    attempts -= 1;
+                    let pos = self.addrs.iter().position(|&a| a == self.addr).unwrap_or(0);
+                    let next = self.addrs[(pos + 1) % self.addrs.len()];
+                    eprintln!(
+                        "{} did not respond on {}, trying {}",
+                        self.url, self.addr, next
+                    );
+                    if next.is_ipv4() != self.addr.is_ipv4() {
+                        self.socket = bind(next)?;
+                    }
+                    self.addr = next;
+                    // Connection ids are only valid for the address they came from
+                    self.connection = None;
+                }
+                res => return res,
+            }
+        }
+    }
+
+    /// Send `packet` and wait for the response to the transaction it contains
+    fn transact(&self, packet: &[u8]) -> Result<Vec<u8>> {
+        let transaction_id = &packet[12..16];
+        let mut buf = [0; 2048];
+        self.socket
+            .send_to(packet, self.addr)
+            .with_context(|| Error::TrackerUnreachable(self.url.clone()))?;
+        let deadline = Instant::now() + RESPONSE_TIMEOUT;
+        while let Some(timeout) = deadline.checked_duration_since(Instant::now()) {
+            self.socket
+                .set_read_timeout(Some(timeout.max(Duration::from_millis(1))))?;
+            let len = match self.socket.recv_from(&mut buf) {
+                Ok((len, from)) if from == self.addr => len,
+                Ok(_) => continue,
+                Err(_) => break,
+            };
+            if len < 8 || &buf[4..8] != transaction_id {
+                continue;
+            }
+            let action = u32::from_be_bytes(buf[0..4].try_into().unwrap());
+            if action == ACTION_ERROR {
+                bail!(
+                    "tracker responded with failure: {}",
+                    String::from_utf8_lossy(&buf[8..len])
+                );
+            }
+            return Ok(buf[..len].to_vec());
+        }
+        Err(Error::TrackerUnreachable(self.url.clone()).into())
+    }
+
+    /// Get a connection id, reusing the last one while it is still valid
+    fn connection_id(&mut self) -> Result<u64> {
+        if let Some((id, obtained)) = self.connection {
+            if obtained.elapsed() < CONNECTION_ID_LIFETIME {
+                return Ok(id);
+            }
+        }
+        let mut packet = Vec::with_capacity(16);
+        packet.extend_from_slice(&PROTOCOL_ID.to_be_bytes());
+        packet.extend_from_slice(&ACTION_CONNECT.to_be_bytes());
+        packet.extend_from_slice(&random::<u32>().to_be_bytes());
+
+        let res = self.transact(&packet)?;
+        ensure!(res.len() >= 16, "connect response is too short");
+        let id = u64::from_be_bytes(res[8..16].try_into().unwrap());
+        self.connection = Some((id, Instant::now()));
+        Ok(id)
+    }
+}
+
+fn event(req: &TrackerAnnounce) -> u32 {
+    match req.event {
+        None => 0,
+        Some(Event::Completed) => 1,
+        Some(Event::Started) => 2,
+        Some(Event::Stopped) => 3,
+    }
+}
+
+/// Only IPv4 addresses fit into the packet, 0 lets the tracker use the sender's
+fn ip(req: &TrackerAnnounce) -> Ipv4Addr {
+    req.ip
+        .as_ref()
+        .and_then(|ip| ip.parse().ok())
+        .unwrap_or(Ipv4Addr::UNSPECIFIED)
+}
+
+/// -1 leaves the number of peers up to the tracker
+fn numwant(req: &TrackerAnnounce) -> i32 {
+    req.numwant.map_or(-1, |n| n as i32)
+}
+
+/// The fields of the announce packet, for showing to humans
+pub fn describe_announce(req: &TrackerAnnounce) -> Vec<(String, String)> {
+    let event = match req.event {
+        Some(e) => format!("{} ({})", event(req), e.as_str()),
+        None => "0 (none)".to_string(),
+    };
+    [
+        (
+            "info_hash",
+            format!("{} (hex)", hex::encode(req.info_hash.truncated())),
+        ),
+        ("peer_id", req.peer_id.to_string()),
+        ("downloaded", req.downloaded.to_string()),
+        ("left", req.left.to_string()),
+        ("uploaded", req.uploaded.to_string()),
+        ("event", event),
+        ("ip", ip(req).to_string()),
+        ("key", format!("{:08X}", req.key.unwrap_or(0))),
+        ("num_want", numwant(req).to_string()),
+        ("port", req.port.to_string()),
+    ]
+    .into_iter()
+    .map(|(name, value)| (name.to_string(), value))
+    .collect()
+}
+
+impl UdpTracker {
+    /// A single announce to the current address
+    fn announce_once(&mut self, req: &TrackerAnnounce) -> Result<TrackerResponse> {
+        let connection_id = self.connection_id()?;
+        let (event, ip, numwant) = (event(req), ip(req), numwant(req));
+
+        let mut packet = Vec::with_capacity(98);
+        packet.extend_from_slice(&connection_id.to_be_bytes());
+        packet.extend_from_slice(&ACTION_ANNOUNCE.to_be_bytes());
+        packet.extend_from_slice(&random::<u32>().to_be_bytes());
+        packet.extend_from_slice(&req.info_hash.truncated());
+        packet.extend_from_slice(req.peer_id.as_bytes());
+        packet.extend_from_slice(&req.downloaded.to_be_bytes());
+        packet.extend_from_slice(&req.left.to_be_bytes());
+        packet.extend_from_slice(&req.uploaded.to_be_bytes());
+        packet.extend_from_slice(&event.to_be_bytes());
+        packet.extend_from_slice(&u32::from(ip).to_be_bytes());
+        packet.extend_from_slice(&req.key.unwrap_or(0).to_be_bytes());
+        packet.extend_from_slice(&numwant.to_be_bytes());
+        packet.extend_from_slice(&req.port.to_be_bytes());
+
+        let res = self.transact(&packet)?;
+        ensure!(res.len() >= 20, "announce response is too short");
+        let field = |i: usize| u32::from_be_bytes(res[i..i + 4].try_into().unwrap()) as i64;
+        // The address family of the peers matches the one we talk to the tracker with
+        let peers = if self.addr.is_ipv6() {
+            compact_peers6(&res[20..])
+        } else {
+            compact_peers(&res[20..])
+        };
+        Ok(TrackerResponse {
+            interval: Some(field(8)),
+            incomplete: Some(field(12)),
+            complete: Some(field(16)),
+            peers,
+            ..Default::default()
+        })
+    }
+
+    fn scrape_once(&mut self, info_hash: &InfoHash) -> Result<ScrapeStats> {
+        let connection_id = self.connection_id()?;
+        let mut packet = Vec::with_capacity(36);
+        packet.extend_from_slice(&connection_id.to_be_bytes());
+        packet.extend_from_slice(&ACTION_SCRAPE.to_be_bytes());
+        packet.extend_from_slice(&random::<u32>().to_be_bytes());
+        packet.extend_from_slice(&info_hash.truncated());
+
+        let res = self.transact(&packet)?;
+        ensure!(res.len() >= 20, "scrape response is too short");
+        let field = |i: usize| u32::from_be_bytes(res[i..i + 4].try_into().unwrap()) as i64;
+        Ok(ScrapeStats {
+            complete: field(8),
+            downloaded: field(12),
+            incomplete: field(16),
+        })
+    }
+}
+
+impl Tracker for UdpTracker {
+    fn supports_url(url: &TrackerUrl) -> bool {
+        matches!(url, TrackerUrl::Udp(_))
+    }
+
+    fn announce(&mut self, req: &TrackerAnnounce) -> Result<TrackerResponse> {
+        self.with_fallback(|tracker| tracker.announce_once(req))
+    }
+
+    fn scrape(&mut self, info_hash: &InfoHash) -> Result<ScrapeStats> {
+        self.with_fallback(|tracker| tracker.scrape_once(info_hash))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn ip_literals_are_used_as_they_are() {
+        let tracker = UdpTracker::new("udp://[::1]:6969/announce").unwrap();
+        assert_eq!(tracker.addr, "[::1]:6969".parse().unwrap());
+        let tracker = UdpTracker::new("udp://127.0.0.1:6969/announce").unwrap();
+        assert_eq!(tracker.addr, "127.0.0.1:6969".parse().unwrap());
+        assert!(UdpTracker::new("udp://127.0.0.1/announce").is_err());
+    }
+
+    #[test]
+    fn other_addresses_are_tried_when_one_fails() {
+        // Answers a connect and an announce with one peer
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut buf = [0; 128];
+            for action in [ACTION_CONNECT, ACTION_ANNOUNCE] {
+                let (_, from) = server.recv_from(&mut buf).unwrap();
+                let mut res = action.to_be_bytes().to_vec();
+                res.extend_from_slice(&buf[12..16]);
+                if action == ACTION_CONNECT {
+                    res.extend_from_slice(&42u64.to_be_bytes());
+                } else {
+                    res.extend_from_slice(&[0, 0, 7, 8, 0, 0, 0, 1, 0, 0, 0, 2]);
+                    res.extend_from_slice(&[10, 0, 0, 1, 0x1a, 0xe1]);
+                }
+                server.send_to(&res, from).unwrap();
+            }
+        });
+
+        let mut tracker = UdpTracker::new("udp://[::1]:6969/announce").unwrap();
+        // Nothing can be sent to port 0, so the first address fails right away
+        tracker.addrs = vec!["[::1]:0".parse().unwrap(), addr];
+        tracker.addr = tracker.addrs[0];
+        let req = TrackerAnnounce::new(InfoHash::V1([0; 20]), [0; 20].into());
+        let res = tracker.announce(&req).unwrap();
+        assert_eq!(tracker.addr, addr);
+        assert_eq!(res.interval, Some(1800));
+        assert_eq!(res.peers, vec!["10.0.0.1:6881".parse().unwrap()]);
+    }
+}
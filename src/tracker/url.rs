@@ -0,0 +1,50 @@
+use anyhow::{anyhow, bail, Context, Result};
+use reqwest::Url;
+use std::fmt;
+use std::str::FromStr;
+
+/// The announce URL of a tracker, with the scheme deciding how to talk to it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrackerUrl {
+    Http(Url),
+    Https(Url),
+    Udp(Url),
+    Ws(Url),
+    Wss(Url),
+}
+
+impl TrackerUrl {
+    pub fn as_str(&self) -> &str {
+        match self {
+            TrackerUrl::Http(url)
+            | TrackerUrl::Https(url)
+            | TrackerUrl::Udp(url)
+            | TrackerUrl::Ws(url)
+            | TrackerUrl::Wss(url) => url.as_str(),
+        }
+    }
+}
+
+impl FromStr for TrackerUrl {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<TrackerUrl> {
+        let url = Url::parse(s).with_context(|| format!("invalid tracker url {:?}", s))?;
+        url.host_str()
+            .ok_or(anyhow!("tracker url {:?} does not contain a host", s))?;
+        Ok(match url.scheme() {
+            "http" => TrackerUrl::Http(url),
+            "https" => TrackerUrl::Https(url),
+            "udp" => TrackerUrl::Udp(url),
+            "ws" => TrackerUrl::Ws(url),
+            "wss" => TrackerUrl::Wss(url),
+            _ => bail!("unsupported tracker protocol in {}", s),
+        })
+    }
+}
+
+impl fmt::Display for TrackerUrl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
@@ -0,0 +1,235 @@
+use super::{ScrapeStats, Tracker, TrackerAnnounce, TrackerConfig, TrackerResponse, TrackerUrl};
+use crate::error::Error;
+use crate::info_hash::InfoHash;
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::net::TcpStream;
+use std::time::Duration;
+use tungstenite::stream::MaybeTlsStream;
+use tungstenite::{Connector, Message, WebSocket};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const READ_TIMEOUT: Duration = Duration::from_secs(15);
+
+type Socket = WebSocket<MaybeTlsStream<TcpStream>>;
+
+/// The JSON messages of a WebTorrent tracker. Announce and scrape responses
+/// share one shape, only the fields belonging to `action` are present.
+#[derive(Debug, Deserialize)]
+struct WsResponse {
+    #[serde(default)]
+    action: Option<String>,
+    #[serde(default)]
+    #[serde(rename = "failure reason")]
+    failure_reason: Option<String>,
+    #[serde(default)]
+    #[serde(rename = "warning message")]
+    warning_message: Option<String>,
+    #[serde(default)]
+    interval: Option<i64>,
+    #[serde(default)]
+    complete: Option<i64>,
+    #[serde(default)]
+    incomplete: Option<i64>,
+    #[serde(default)]
+    files: HashMap<String, ScrapeStats>,
+}
+
+/// Tracker of a WebTorrent swarm, reached via JSON messages over a WebSocket.
+///
+/// The peers of these swarms are browsers that can only be reached over
+/// WebRTC, so we announce without offers and never get any peers back. The
+/// swarm statistics are still useful, and the other trackers of hybrid
+/// torrents hand out the peers we can actually connect to.
+pub struct WsTracker {
+    url: String,
+    /// Used for wss:// URLs, set up from the same options as HTTPS trackers
+    tls: native_tls::TlsConnector,
+    socket: Option<Socket>,
+}
+
+impl WsTracker {
+    pub fn new(url: &str, config: &TrackerConfig) -> Result<WsTracker> {
+        let mut tls = native_tls::TlsConnector::builder();
+        tls.danger_accept_invalid_certs(config.insecure);
+        for pem in config.ca_certificates()? {
+            let cert = native_tls::Certificate::from_pem(pem.as_bytes())
+                .context("invalid certificate in the CA bundle")?;
+            tls.add_root_certificate(cert);
+        }
+        Ok(WsTracker {
+            url: url.to_string(),
+            tls: tls.build()?,
+            socket: None,
+        })
+    }
+
+    /// Open the TCP connection with a timeout and do the WebSocket handshake on it
+    fn connect(&self) -> Result<Socket> {
+        let url = reqwest::Url::parse(&self.url)?;
+        let mut last_err = anyhow!("{} does not resolve to any address", self.url);
+        // Knows the default ports of ws and wss and takes IPv6 literals in brackets
+        for addr in url.socket_addrs(|| None)? {
+            match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+                Ok(stream) => {
+                    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+                    stream.set_write_timeout(Some(READ_TIMEOUT))?;
+                    let connector = Connector::NativeTls(self.tls.clone());
+                    let (socket, _) = tungstenite::client_tls_with_config(
+                        self.url.as_str(),
+                        stream,
+                        None,
+                        Some(connector),
+                    )?;
+                    return Ok(socket);
+                }
+                Err(e) => last_err = e.into(),
+            }
+        }
+        Err(last_err)
+    }
+
+    /// Send `request` and wait for the response with the same action,
+    /// (re)connecting first if necessary
+    fn transact(&mut self, request: serde_json::Value) -> Result<WsResponse> {
+        if self.socket.is_none() {
+            let socket = self
+                .connect()
+                .with_context(|| Error::TrackerUnreachable(self.url.clone()))?;
+            self.socket = Some(socket);
+        }
+        let socket = self.socket.as_mut().unwrap();
+
+        let res = match exchange(socket, request) {
+            // The connection is broken, start over with a new one next time
+            Err(e) if e.is::<tungstenite::Error>() => {
+                self.socket = None;
+                return Err(e.context(Error::TrackerUnreachable(self.url.clone())));
+            }
+            res => res?,
+        };
+        if let Some(reason) = res.failure_reason {
+            bail!("tracker responded with failure: {}", reason);
+        }
+        Ok(res)
+    }
+}
+
+fn exchange(socket: &mut Socket, request: serde_json::Value) -> Result<WsResponse> {
+    let action = request["action"].clone();
+    socket.send(Message::Text(request.to_string()))?;
+    loop {
+        // Pings are answered by tungstenite, offers from other peers ignored
+        if let Message::Text(text) = socket.read()? {
+            let res: WsResponse =
+                serde_json::from_str(&text).context("failed to parse the tracker response")?;
+            if res.action.as_deref() == action.as_str() {
+                return Ok(res);
+            }
+        }
+    }
+}
+
+/// WebTorrent sends raw bytes as strings with one char per byte
+fn binary_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| b as char).collect()
+}
+
+fn announce_request(req: &TrackerAnnounce) -> serde_json::Value {
+    let mut request = json!({
+        "action": "announce",
+        "info_hash": binary_string(&req.info_hash.truncated()),
+        "peer_id": binary_string(req.peer_id.as_bytes()),
+        "uploaded": req.uploaded,
+        "downloaded": req.downloaded,
+        "left": req.left,
+        "numwant": 0,
+        "offers": [],
+    });
+    if let Some(event) = req.event {
+        request["event"] = event.as_str().into();
+    }
+    request
+}
+
+/// The fields of the announce message, for showing to humans
+pub fn describe_announce(req: &TrackerAnnounce) -> Vec<(String, String)> {
+    let request = announce_request(req);
+    let fields = request.as_object().unwrap().iter();
+    fields
+        .map(|(name, value)| {
+            let value = match name.as_str() {
+                "info_hash" => format!("{} (hex)", hex::encode(req.info_hash.truncated())),
+                "peer_id" => req.peer_id.to_string(),
+                _ => value.as_str().map_or(value.to_string(), str::to_string),
+            };
+            (name.clone(), value)
+        })
+        .collect()
+}
+
+impl Tracker for WsTracker {
+    fn supports_url(url: &TrackerUrl) -> bool {
+        matches!(url, TrackerUrl::Ws(_) | TrackerUrl::Wss(_))
+    }
+
+    fn announce(&mut self, req: &TrackerAnnounce) -> Result<TrackerResponse> {
+        let res = self.transact(announce_request(req))?;
+        Ok(TrackerResponse {
+            warning_message: res.warning_message,
+            interval: res.interval,
+            complete: res.complete,
+            incomplete: res.incomplete,
+            ..Default::default()
+        })
+    }
+
+    fn scrape(&mut self, info_hash: &InfoHash) -> Result<ScrapeStats> {
+        let info_hash = binary_string(&info_hash.truncated());
+        let mut res = self.transact(json!({
+            "action": "scrape",
+            "info_hash": [info_hash],
+        }))?;
+        res.files
+            .remove(&info_hash)
+            .ok_or(anyhow!("tracker does not know this torrent"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Instant;
+
+    #[test]
+    fn ipv6_literals_are_connected_to() {
+        let listener = TcpListener::bind("[::1]:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let url = format!(
+            "ws://[::1]:{}/announce",
+            listener.local_addr().unwrap().port()
+        );
+        // Reads the start of the handshake and hangs up
+        let server = thread::spawn(move || {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while Instant::now() < deadline {
+                if let Ok((mut stream, _)) = listener.accept() {
+                    stream.set_nonblocking(false).unwrap();
+                    let mut buf = [0; 13];
+                    stream.read_exact(&mut buf).unwrap();
+                    return buf.to_vec();
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+            Vec::new()
+        });
+        let tracker = WsTracker::new(&url, &TrackerConfig::default()).unwrap();
+        assert!(tracker.connect().is_err());
+        assert_eq!(server.join().unwrap(), b"GET /announce");
+    }
+}